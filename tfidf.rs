@@ -1,15 +1,218 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::path::Path;
 
+use rayon::prelude::*;
+use rust_stemmers::{Algorithm, Stemmer};
+
 const LINES_PER_DOCUMENT: usize = 1000;
 
+/// Appends bits MSB-first into a byte buffer. Used to pack vbyte-coded
+/// gaps and gamma-coded frequencies into a single compact postings blob;
+/// the two encodings don't need to stay byte-aligned with each other.
+struct BitsWriter {
+    bytes: Vec<u8>,
+    cur: u8,
+    nbits: u8,
+}
+
+impl BitsWriter {
+    fn new() -> Self {
+        BitsWriter { bytes: Vec::new(), cur: 0, nbits: 0 }
+    }
+
+    fn write_bit(&mut self, bit: u8) {
+        self.cur = (self.cur << 1) | (bit & 1);
+        self.nbits += 1;
+        if self.nbits == 8 {
+            self.bytes.push(self.cur);
+            self.cur = 0;
+            self.nbits = 0;
+        }
+    }
+
+    fn write_bits(&mut self, value: u64, n: u32) {
+        for i in (0..n).rev() {
+            self.write_bit(((value >> i) & 1) as u8);
+        }
+    }
+
+    /// Variable-byte: 7 data bits per byte, high bit set on the final byte.
+    fn write_vbyte(&mut self, value: u64) {
+        let mut groups = Vec::new();
+        let mut remaining = value;
+        loop {
+            groups.push((remaining & 0x7F) as u8);
+            remaining >>= 7;
+            if remaining == 0 {
+                break;
+            }
+        }
+        groups.reverse();
+        let last = groups.len() - 1;
+        for (i, group) in groups.into_iter().enumerate() {
+            let byte = if i == last { group | 0x80 } else { group };
+            self.write_bits(byte as u64, 8);
+        }
+    }
+
+    /// Elias gamma: floor(log2(x)) zero bits, then x in binary, MSB first.
+    fn write_gamma(&mut self, value: u64) {
+        debug_assert!(value >= 1, "gamma coding requires x >= 1");
+        let k = 63 - value.leading_zeros();
+        for _ in 0..k {
+            self.write_bit(0);
+        }
+        self.write_bits(value, k + 1);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            self.cur <<= 8 - self.nbits;
+            self.bytes.push(self.cur);
+        }
+        self.bytes
+    }
+}
+
+/// Reads bits MSB-first out of a byte buffer produced by `BitsWriter`.
+struct BitsReader<'a> {
+    bytes: &'a [u8],
+    bit_pos: usize,
+}
+
+impl<'a> BitsReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitsReader { bytes, bit_pos: 0 }
+    }
+
+    fn read_bit(&mut self) -> u8 {
+        let byte = self.bytes[self.bit_pos / 8];
+        let bit = (byte >> (7 - (self.bit_pos % 8))) & 1;
+        self.bit_pos += 1;
+        bit
+    }
+
+    fn read_bits(&mut self, n: u32) -> u64 {
+        let mut value = 0u64;
+        for _ in 0..n {
+            value = (value << 1) | self.read_bit() as u64;
+        }
+        value
+    }
+
+    fn read_vbyte(&mut self) -> u64 {
+        let mut value = 0u64;
+        loop {
+            let byte = self.read_bits(8);
+            value = (value << 7) | (byte & 0x7F);
+            if byte & 0x80 != 0 {
+                break;
+            }
+        }
+        value
+    }
+
+    fn read_gamma(&mut self) -> u64 {
+        let mut zeros = 0;
+        while self.read_bit() == 0 {
+            zeros += 1;
+        }
+        let mut value = 1u64;
+        for _ in 0..zeros {
+            value = (value << 1) | self.read_bit() as u64;
+        }
+        value
+    }
+}
+
+/// Smoothing scheme used to turn document frequency into an IDF weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IdfMethod {
+    /// `ln((1 + N)/(1 + df)) + 1`: scikit-learn-style smoothing. The `+1`
+    /// inside the log adds a virtual document containing every term, so
+    /// `df` can never reach `N + 1` and division by zero is impossible;
+    /// the trailing `+1` keeps terms present in every document at weight
+    /// 1 instead of being zeroed out.
+    Smooth,
+    /// `ln(N/df) + 1`: the same ratio as `Smooth` without the virtual
+    /// document, offset so ubiquitous terms still carry weight 1.
+    NonSmooth,
+    /// `ln(N/(1 + df))`: the classic IR-textbook definition. A term in
+    /// `N - 1` documents gets weight 0, and a term in every document gets
+    /// a small negative weight.
+    Textbook,
+}
+
+/// Controls how raw text is turned into terms before counting: casing and
+/// punctuation are always normalized. Stemming is on by default and can be
+/// turned off with `stem`; stop-word filtering applies only to the words
+/// placed in `stop_words`, which starts empty.
+#[derive(Debug, Clone)]
+struct TokenizerConfig {
+    stem: bool,
+    language: Algorithm,
+    stop_words: HashSet<String>,
+}
+
+impl TokenizerConfig {
+    fn new(language: Algorithm) -> Self {
+        TokenizerConfig {
+            stem: true,
+            language,
+            stop_words: HashSet::new(),
+        }
+    }
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        TokenizerConfig::new(Algorithm::English)
+    }
+}
+
+/// Output format for the command-line report, selected with `--format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+/// A candidate result ordered by score, for use in the bounded min-heap
+/// that `TfidfCalculator::query` uses to track the top-k documents.
+#[derive(Debug, PartialEq)]
+struct ScoredDoc {
+    score: f64,
+    doc_id: usize,
+}
+
+impl Eq for ScoredDoc {}
+
+impl PartialOrd for ScoredDoc {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScoredDoc {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.score.partial_cmp(&other.score).unwrap_or(std::cmp::Ordering::Equal)
+    }
+}
+
 #[derive(Debug)]
 struct TfidfCalculator {
     document_frequency: HashMap<String, usize>,
     term_frequencies: Vec<HashMap<String, usize>>,
+    document_lengths: Vec<usize>,
     n_documents: usize,
+    k1: f64,
+    b: f64,
+    idf_method: IdfMethod,
+    tokenizer_config: TokenizerConfig,
 }
 
 impl TfidfCalculator {
@@ -17,121 +220,710 @@ impl TfidfCalculator {
         TfidfCalculator {
             document_frequency: HashMap::new(),
             term_frequencies: Vec::new(),
+            document_lengths: Vec::new(),
             n_documents: 0,
+            k1: 1.2,
+            b: 0.75,
+            idf_method: IdfMethod::Smooth,
+            tokenizer_config: TokenizerConfig::default(),
         }
     }
 
-    fn tokenize(text: &str) -> Vec<String> {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        Self::tokenize_with(&self.tokenizer_config, text)
+    }
+
+    fn tokenize_with(config: &TokenizerConfig, text: &str) -> Vec<String> {
+        let stemmer = config.stem.then(|| Stemmer::create(config.language));
+
         text.split_whitespace()
             .map(|word| word.to_lowercase()
                 .chars()
                 .filter(|c| c.is_alphanumeric())
                 .collect::<String>())
             .filter(|word| !word.is_empty())
+            .filter(|word| !config.stop_words.contains(word))
+            .map(|word| match &stemmer {
+                Some(stemmer) => stemmer.stem(&word).into_owned(),
+                None => word,
+            })
             .collect()
     }
 
-    fn process_document(&mut self, text: &str) {
-        let tokens = Self::tokenize(text);
-        let mut term_freq = HashMap::new();
-        let mut seen_terms = HashMap::new();
+    /// Tokenizes and counts every document in parallel with rayon, then
+    /// sequentially folds each document's term-frequency map into the
+    /// shared `document_frequency` accumulator. The per-document counting
+    /// is the expensive part and scales across cores; the final merge is
+    /// a cheap single-threaded pass, so there's no lock contention on the
+    /// shared accumulator.
+    fn process_documents_parallel(&mut self, documents: Vec<String>) {
+        let config = self.tokenizer_config.clone();
+        let per_document: Vec<(HashMap<String, usize>, usize)> = documents
+            .par_iter()
+            .map(|text| {
+                let mut term_freq = HashMap::new();
+                for token in Self::tokenize_with(&config, text) {
+                    *term_freq.entry(token).or_insert(0) += 1;
+                }
+                let doc_length = term_freq.values().sum();
+                (term_freq, doc_length)
+            })
+            .collect();
 
-        for token in tokens {
-            *term_freq.entry(token.clone()).or_insert(0) += 1;
-            seen_terms.insert(token, true);
+        for (term_freq, doc_length) in per_document {
+            for term in term_freq.keys() {
+                *self.document_frequency.entry(term.clone()).or_insert(0) += 1;
+            }
+            self.document_lengths.push(doc_length);
+            self.term_frequencies.push(term_freq);
+            self.n_documents += 1;
         }
+    }
+
+    fn calculate_tfidf(&self, method: IdfMethod) -> Vec<HashMap<String, f64>> {
+        let n = self.n_documents as f64;
+
+        self.term_frequencies
+            .par_iter()
+            .map(|doc_tf| {
+                let mut doc_tfidf = HashMap::new();
+
+                for (term, freq) in doc_tf {
+                    if let Some(&df) = self.document_frequency.get(term) {
+                        let df = df as f64;
+                        let tf = *freq as f64;
+                        let idf = match method {
+                            IdfMethod::Smooth => ((1.0 + n) / (1.0 + df)).ln() + 1.0,
+                            IdfMethod::NonSmooth => (n / df).ln() + 1.0,
+                            IdfMethod::Textbook => (n / (1.0 + df)).ln(),
+                        };
+                        let tfidf = tf * idf;
+                        doc_tfidf.insert(term.clone(), tfidf);
+                    }
+                }
+
+                doc_tfidf
+            })
+            .collect()
+    }
+
+    /// L2-normalizes each document's score map: every term weight is
+    /// divided by the Euclidean norm of that document's vector, so
+    /// documents become directly comparable regardless of length.
+    fn calculate_tfidf_normalized(&self, method: IdfMethod) -> Vec<HashMap<String, f64>> {
+        let mut scores = self.calculate_tfidf(method);
 
-        for term in seen_terms.keys() {
-            *self.document_frequency.entry(term.clone()).or_insert(0) += 1;
+        for doc_scores in &mut scores {
+            let norm = doc_scores.values().map(|w| w * w).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                for weight in doc_scores.values_mut() {
+                    *weight /= norm;
+                }
+            }
         }
 
-        self.term_frequencies.push(term_freq);
-        self.n_documents += 1;
+        scores
     }
 
-    fn calculate_tfidf(&self) -> Vec<HashMap<String, f64>> {
-        let mut tfidf_scores = Vec::new();
+    /// Cosine similarity between two documents' L2-normalized TF-IDF
+    /// vectors, computed by iterating the smaller sparse map and looking
+    /// up matching terms in the larger one.
+    fn cosine_similarity(&self, doc_a: usize, doc_b: usize) -> f64 {
+        let normalized = self.calculate_tfidf_normalized(self.idf_method);
+        let vec_a = &normalized[doc_a];
+        let vec_b = &normalized[doc_b];
+
+        let (smaller, larger) = if vec_a.len() <= vec_b.len() {
+            (vec_a, vec_b)
+        } else {
+            (vec_b, vec_a)
+        };
+
+        smaller
+            .iter()
+            .filter_map(|(term, weight)| larger.get(term).map(|other| weight * other))
+            .sum()
+    }
+
+    /// Okapi BM25: adds term-frequency saturation and document-length
+    /// normalization on top of plain TF-IDF.
+    fn calculate_bm25(&self) -> Vec<HashMap<String, f64>> {
+        let n = self.n_documents as f64;
+        let avgdl = if self.document_lengths.is_empty() {
+            0.0
+        } else {
+            self.document_lengths.iter().sum::<usize>() as f64 / self.document_lengths.len() as f64
+        };
+
+        let mut bm25_scores = Vec::new();
+
+        for (doc_idx, doc_tf) in self.term_frequencies.iter().enumerate() {
+            let doc_len = self.document_lengths[doc_idx] as f64;
+            let mut doc_scores = HashMap::new();
 
-        for doc_tf in &self.term_frequencies {
-            let mut doc_tfidf = HashMap::new();
-            
             for (term, freq) in doc_tf {
                 if let Some(&df) = self.document_frequency.get(term) {
+                    let df = df as f64;
+                    let idf = (((n - df + 0.5) / (df + 0.5)) + 1.0).ln();
                     let tf = *freq as f64;
-                    let idf = (self.n_documents as f64 / df as f64).ln();
-                    let tfidf = tf * idf;
-                    doc_tfidf.insert(term.clone(), tfidf);
+                    let denom = tf + self.k1 * (1.0 - self.b + self.b * doc_len / avgdl);
+                    let score = idf * (tf * (self.k1 + 1.0)) / denom;
+                    doc_scores.insert(term.clone(), score);
+                }
+            }
+
+            bm25_scores.push(doc_scores);
+        }
+
+        bm25_scores
+    }
+
+    /// Serializes the model to disk as a compressed inverted index: after a
+    /// header with the document count and vocabulary size, each term is
+    /// written in sorted order as its text and document frequency in plain
+    /// form, followed directly by its postings list (document id, term
+    /// frequency) as gap vbytes and gamma-coded frequencies, so a corpus can be indexed once
+    /// and queried many times without retokenizing.
+    fn write_index(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+
+        let mut vocabulary: Vec<&String> = self.document_frequency.keys().collect();
+        vocabulary.sort();
+
+        file.write_all(&(self.n_documents as u32).to_le_bytes())?;
+        file.write_all(&(vocabulary.len() as u32).to_le_bytes())?;
+
+        for term in vocabulary {
+            let df = self.document_frequency[term];
+            let postings: Vec<(usize, usize)> = self
+                .term_frequencies
+                .iter()
+                .enumerate()
+                .filter_map(|(doc_id, doc_tf)| doc_tf.get(term).map(|&tf| (doc_id, tf)))
+                .collect();
+
+            let term_bytes = term.as_bytes();
+            file.write_all(&(term_bytes.len() as u16).to_le_bytes())?;
+            file.write_all(term_bytes)?;
+            file.write_all(&(df as u32).to_le_bytes())?;
+
+            let mut writer = BitsWriter::new();
+            let mut last_doc_id = 0usize;
+            for (doc_id, tf) in &postings {
+                let gap = doc_id - last_doc_id;
+                writer.write_vbyte(gap as u64);
+                writer.write_gamma(*tf as u64);
+                last_doc_id = *doc_id;
+            }
+            let blob = writer.finish();
+            file.write_all(&(blob.len() as u32).to_le_bytes())?;
+            file.write_all(&blob)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reconstructs a `TfidfCalculator` from an index written by
+    /// `write_index`. Runtime-only configuration (tokenizer, IDF method,
+    /// BM25 constants) isn't part of the serialized corpus and is reset
+    /// to the calculator's defaults.
+    fn read_index(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let mut cursor = 0usize;
+
+        let read_u32 = |data: &[u8], cursor: &mut usize| -> u32 {
+            let value = u32::from_le_bytes(data[*cursor..*cursor + 4].try_into().unwrap());
+            *cursor += 4;
+            value
+        };
+        let read_u16 = |data: &[u8], cursor: &mut usize| -> u16 {
+            let value = u16::from_le_bytes(data[*cursor..*cursor + 2].try_into().unwrap());
+            *cursor += 2;
+            value
+        };
+
+        let n_documents = read_u32(&data, &mut cursor) as usize;
+        let vocab_size = read_u32(&data, &mut cursor) as usize;
+
+        let mut calculator = TfidfCalculator::new();
+        calculator.n_documents = n_documents;
+        calculator.term_frequencies = vec![HashMap::new(); n_documents];
+        calculator.document_lengths = vec![0; n_documents];
+
+        for _ in 0..vocab_size {
+            let term_len = read_u16(&data, &mut cursor) as usize;
+            let term = String::from_utf8(data[cursor..cursor + term_len].to_vec())
+                .expect("index term is not valid UTF-8");
+            cursor += term_len;
+
+            let df = read_u32(&data, &mut cursor) as usize;
+            calculator.document_frequency.insert(term.clone(), df);
+
+            let blob_len = read_u32(&data, &mut cursor) as usize;
+            let blob = &data[cursor..cursor + blob_len];
+            cursor += blob_len;
+
+            let mut reader = BitsReader::new(blob);
+            let mut doc_id = 0usize;
+            for _ in 0..df {
+                doc_id += reader.read_vbyte() as usize;
+                let tf = reader.read_gamma() as usize;
+                calculator.term_frequencies[doc_id].insert(term.clone(), tf);
+                calculator.document_lengths[doc_id] += tf;
+            }
+        }
+
+        Ok(calculator)
+    }
+
+    /// Tokenizes `text` with the same pipeline used when indexing, scores
+    /// every document against the query terms with BM25, and returns the
+    /// top `top_k` documents by score using a bounded min-heap: each
+    /// candidate is pushed, and once the heap grows past `top_k` the
+    /// smallest score is popped back off.
+    fn query(&self, text: &str, top_k: usize) -> Vec<(usize, f64)> {
+        let query_terms = self.tokenize(text);
+        let bm25_scores = self.calculate_bm25();
+
+        let mut doc_scores: HashMap<usize, f64> = HashMap::new();
+        for term in &query_terms {
+            for (doc_id, doc_score_map) in bm25_scores.iter().enumerate() {
+                if let Some(&score) = doc_score_map.get(term) {
+                    *doc_scores.entry(doc_id).or_insert(0.0) += score;
                 }
             }
-            
-            tfidf_scores.push(doc_tfidf);
         }
 
-        tfidf_scores
+        let mut heap: BinaryHeap<Reverse<ScoredDoc>> = BinaryHeap::new();
+        for (doc_id, score) in doc_scores {
+            heap.push(Reverse(ScoredDoc { score, doc_id }));
+            if heap.len() > top_k {
+                heap.pop();
+            }
+        }
+
+        // `into_sorted_vec` sorts ascending by `Reverse<ScoredDoc>`, which is
+        // descending by score — exactly the highest-score-first order we want.
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(candidate)| (candidate.doc_id, candidate.score))
+            .collect()
     }
+
+    /// Writes the `limit` most common words (by document frequency,
+    /// descending) to `out` in the given format, for piping into
+    /// downstream analysis instead of scraping console text.
+    fn write_most_common_words(
+        &self,
+        limit: usize,
+        format: OutputFormat,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut words: Vec<(&String, &usize)> = self.document_frequency.iter().collect();
+        words.sort_by(|a, b| b.1.cmp(a.1));
+        words.truncate(limit);
+
+        match format {
+            OutputFormat::Csv => {
+                writeln!(out, "word,document_frequency")?;
+                for (word, df) in &words {
+                    writeln!(out, "{},{}", word, df)?;
+                }
+            }
+            OutputFormat::Json => {
+                let rows: Vec<String> = words
+                    .iter()
+                    .map(|(word, df)| format!("{{\"word\":\"{}\",\"document_frequency\":{}}}", word, df))
+                    .collect();
+                writeln!(out, "[{}]", rows.join(","))?;
+            }
+            OutputFormat::Text => {
+                for (word, df) in &words {
+                    writeln!(out, "{:<20} {}", word, df)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes `document id, term, score` rows for every document's TF-IDF
+    /// (or BM25) scores as CSV.
+    fn write_tfidf_csv(
+        &self,
+        scores: &[HashMap<String, f64>],
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        writeln!(out, "document_id,term,score")?;
+        for (doc_id, doc_scores) in scores.iter().enumerate() {
+            for (term, score) in doc_scores {
+                writeln!(out, "{},{},{}", doc_id, term, score)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `document id, term, score` rows for every document's TF-IDF
+    /// (or BM25) scores as a JSON array.
+    fn write_tfidf_json(
+        &self,
+        scores: &[HashMap<String, f64>],
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let rows: Vec<String> = scores
+            .iter()
+            .enumerate()
+            .flat_map(|(doc_id, doc_scores)| {
+                doc_scores.iter().map(move |(term, score)| {
+                    format!(
+                        "{{\"document_id\":{},\"term\":\"{}\",\"score\":{}}}",
+                        doc_id, term, score
+                    )
+                })
+            })
+            .collect();
+        writeln!(out, "[{}]", rows.join(","))?;
+        Ok(())
+    }
+}
+
+struct CliArgs {
+    filename: Option<String>,
+    format: OutputFormat,
+    write_index: Option<String>,
+    load_index: Option<String>,
+    query: Option<String>,
+    top_k: usize,
+    idf_method: IdfMethod,
+    similarity: Option<(usize, usize)>,
+}
+
+fn parse_args(args: &[String]) -> CliArgs {
+    let mut filename = None;
+    let mut format = OutputFormat::Text;
+    let mut write_index = None;
+    let mut load_index = None;
+    let mut query = None;
+    let mut top_k = 10;
+    let mut idf_method = IdfMethod::Smooth;
+    let mut similarity = None;
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--format" => {
+                i += 1;
+                format = match args.get(i).map(String::as_str) {
+                    Some("text") => OutputFormat::Text,
+                    Some("csv") => OutputFormat::Csv,
+                    Some("json") => OutputFormat::Json,
+                    _ => {
+                        eprintln!("--format expects one of: text, csv, json");
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--idf-method" => {
+                i += 1;
+                idf_method = match args.get(i).map(String::as_str) {
+                    Some("smooth") => IdfMethod::Smooth,
+                    Some("non-smooth") => IdfMethod::NonSmooth,
+                    Some("textbook") => IdfMethod::Textbook,
+                    _ => {
+                        eprintln!("--idf-method expects one of: smooth, non-smooth, textbook");
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--similarity" => {
+                i += 1;
+                let pair = args.get(i).unwrap_or_else(|| {
+                    eprintln!("--similarity requires a \"doc_a,doc_b\" pair");
+                    std::process::exit(1);
+                });
+                let parsed: Option<(usize, usize)> = pair.split_once(',').and_then(|(a, b)| {
+                    Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+                });
+                similarity = Some(parsed.unwrap_or_else(|| {
+                    eprintln!("--similarity expects \"doc_a,doc_b\", e.g. --similarity 0,1");
+                    std::process::exit(1);
+                }));
+            }
+            "--write-index" => {
+                i += 1;
+                write_index = Some(args.get(i).cloned().unwrap_or_else(|| {
+                    eprintln!("--write-index requires a path");
+                    std::process::exit(1);
+                }));
+            }
+            "--load-index" => {
+                i += 1;
+                load_index = Some(args.get(i).cloned().unwrap_or_else(|| {
+                    eprintln!("--load-index requires a path");
+                    std::process::exit(1);
+                }));
+            }
+            "--query" => {
+                i += 1;
+                query = Some(args.get(i).cloned().unwrap_or_else(|| {
+                    eprintln!("--query requires a search string");
+                    std::process::exit(1);
+                }));
+            }
+            "--top-k" => {
+                i += 1;
+                top_k = args
+                    .get(i)
+                    .and_then(|v| v.parse().ok())
+                    .filter(|&k: &usize| k > 0)
+                    .unwrap_or_else(|| {
+                        eprintln!("--top-k requires a positive integer");
+                        std::process::exit(1);
+                    });
+            }
+            other => filename = Some(other.to_string()),
+        }
+        i += 1;
+    }
+
+    if filename.is_none() && load_index.is_none() {
+        eprintln!(
+            "Usage: {} <filename> [--format text|csv|json] [--write-index path] \
+             [--load-index path] [--query text] [--top-k n] \
+             [--idf-method smooth|non-smooth|textbook] [--similarity doc_a,doc_b]",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+
+    CliArgs { filename, format, write_index, load_index, query, top_k, idf_method, similarity }
 }
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <filename>", args[0]);
-        std::process::exit(1);
+    let cli = parse_args(&args);
+
+    let mut calculator = if let Some(index_path) = &cli.load_index {
+        // Reuse a previously built index instead of retokenizing the corpus.
+        TfidfCalculator::read_index(Path::new(index_path))?
+    } else {
+        let filename = cli
+            .filename
+            .expect("parse_args requires a filename when --load-index is absent");
+        let file = File::open(Path::new(&filename))?;
+        let reader = BufReader::new(file);
+        let mut calculator = TfidfCalculator::new();
+
+        // Split the file into LINES_PER_DOCUMENT-sized chunks first, then hand
+        // the whole batch to rayon so documents are tokenized and counted
+        // across cores instead of one at a time.
+        let mut documents = Vec::new();
+        let mut current_document = String::new();
+        let mut line_count = 0;
+
+        for line in reader.lines() {
+            let line = line?;
+            current_document.push_str(&line);
+            current_document.push('\n');
+            line_count += 1;
+
+            if line_count == LINES_PER_DOCUMENT {
+                documents.push(std::mem::take(&mut current_document));
+                line_count = 0;
+            }
+        }
+
+        // The remaining lines form the last document.
+        if !current_document.is_empty() {
+            documents.push(current_document);
+        }
+
+        calculator.process_documents_parallel(documents);
+        calculator
+    };
+    calculator.idf_method = cli.idf_method;
+
+    if let Some(index_path) = &cli.write_index {
+        calculator.write_index(Path::new(index_path))?;
+    }
+
+    if let Some(query_text) = &cli.query {
+        println!("\nTop {} results for \"{}\":", cli.top_k, query_text);
+        for (doc_id, score) in calculator.query(query_text, cli.top_k) {
+            println!("Document {:<6} {:.4}", doc_id, score);
+        }
+        return Ok(());
+    }
+
+    if let Some((doc_a, doc_b)) = cli.similarity {
+        if doc_a >= calculator.n_documents || doc_b >= calculator.n_documents {
+            eprintln!(
+                "--similarity {},{} is out of range: corpus has {} document(s) (valid indices 0..{})",
+                doc_a,
+                doc_b,
+                calculator.n_documents,
+                calculator.n_documents.saturating_sub(1)
+            );
+            std::process::exit(1);
+        }
+        println!(
+            "Cosine similarity between document {} and {}: {:.4}",
+            doc_a,
+            doc_b,
+            calculator.cosine_similarity(doc_a, doc_b)
+        );
+        return Ok(());
+    }
+
+    let format = cli.format;
+
+    // Calculate TF-IDF scores
+    let tfidf_scores = calculator.calculate_tfidf(calculator.idf_method);
+
+    match format {
+        OutputFormat::Text => {
+            println!("\nTF-IDF Scores by Document:");
+            for (doc_idx, doc_scores) in tfidf_scores.iter().enumerate() {
+                println!("\nDocument {} (Lines {}-{})",
+                        doc_idx + 1,
+                        doc_idx * LINES_PER_DOCUMENT + 1,
+                        (doc_idx + 1) * LINES_PER_DOCUMENT);
+
+                // Sort terms by TF-IDF score
+                let mut scores: Vec<_> = doc_scores.iter().collect();
+                scores.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
+
+                // Print top 10 terms with highest TF-IDF scores
+                for (term, score) in scores.iter().take(10) {
+                    println!("{:<20} {:.4}", term, score);
+                }
+            }
+
+            println!("\nMost Common Words:");
+            calculator.write_most_common_words(10, format, &mut io::stdout())?;
+
+            // Print some statistics
+            println!("\nProcessing Summary:");
+            println!("Total documents processed: {}", calculator.n_documents);
+            println!("Total unique terms: {}", calculator.document_frequency.len());
+            println!("Lines per document: {}", LINES_PER_DOCUMENT);
+        }
+        OutputFormat::Csv => {
+            calculator.write_tfidf_csv(&tfidf_scores, &mut io::stdout())?;
+            // Word-frequency export goes to stderr so it doesn't corrupt the
+            // CSV rows a caller is piping from stdout.
+            calculator.write_most_common_words(10, format, &mut io::stderr())?;
+        }
+        OutputFormat::Json => {
+            calculator.write_tfidf_json(&tfidf_scores, &mut io::stdout())?;
+            calculator.write_most_common_words(10, format, &mut io::stderr())?;
+        }
     }
 
-    let path = Path::new(&args[1]);
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let mut calculator = TfidfCalculator::new();
+    Ok(())
+}
 
-    // Process the file in chunks of LINES_PER_DOCUMENT lines
-    let mut current_document = String::new();
-    let mut line_count = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for line in reader.lines() {
-        let line = line?;
-        current_document.push_str(&line);
-        current_document.push('\n');
-        line_count += 1;
+    #[test]
+    fn bits_vbyte_roundtrip() {
+        let mut writer = BitsWriter::new();
+        let values = [0u64, 1, 127, 128, 300, 16384, u64::from(u32::MAX)];
+        for &v in &values {
+            writer.write_vbyte(v);
+        }
+        let blob = writer.finish();
 
-        // When we reach LINES_PER_DOCUMENT lines or the end of the file,
-        // process the current document
-        if line_count == LINES_PER_DOCUMENT {
-            calculator.process_document(&current_document);
-            current_document.clear();
-            line_count = 0;
+        let mut reader = BitsReader::new(&blob);
+        for &v in &values {
+            assert_eq!(reader.read_vbyte(), v);
         }
     }
 
-    // Process any remaining lines as the last document
-    if !current_document.is_empty() {
-        calculator.process_document(&current_document);
+    #[test]
+    fn bits_gamma_roundtrip() {
+        let mut writer = BitsWriter::new();
+        let values = [1u64, 2, 3, 4, 7, 8, 1000, 65535];
+        for &v in &values {
+            writer.write_gamma(v);
+        }
+        let blob = writer.finish();
+
+        let mut reader = BitsReader::new(&blob);
+        for &v in &values {
+            assert_eq!(reader.read_gamma(), v);
+        }
     }
 
-    // Calculate and print TF-IDF scores
-    let tfidf_scores = calculator.calculate_tfidf();
+    #[test]
+    fn bits_vbyte_and_gamma_interleaved_roundtrip() {
+        let mut writer = BitsWriter::new();
+        writer.write_vbyte(5);
+        writer.write_gamma(3);
+        writer.write_vbyte(300);
+        writer.write_gamma(1);
+        let blob = writer.finish();
 
-    println!("\nTF-IDF Scores by Document:");
-    for (doc_idx, doc_scores) in tfidf_scores.iter().enumerate() {
-        println!("\nDocument {} (Lines {}-{})", 
-                doc_idx + 1, 
-                doc_idx * LINES_PER_DOCUMENT + 1, 
-                (doc_idx + 1) * LINES_PER_DOCUMENT);
-        
-        // Sort terms by TF-IDF score
-        let mut scores: Vec<_> = doc_scores.iter().collect();
-        scores.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
-        
-        // Print top 10 terms with highest TF-IDF scores
-        for (term, score) in scores.iter().take(10) {
-            println!("{:<20} {:.4}", term, score);
+        let mut reader = BitsReader::new(&blob);
+        assert_eq!(reader.read_vbyte(), 5);
+        assert_eq!(reader.read_gamma(), 3);
+        assert_eq!(reader.read_vbyte(), 300);
+        assert_eq!(reader.read_gamma(), 1);
+    }
+
+    fn build_test_calculator() -> TfidfCalculator {
+        let mut calculator = TfidfCalculator::new();
+        calculator.process_documents_parallel(vec![
+            "the quick brown fox the fox".to_string(),
+            "the lazy dog sleeps".to_string(),
+            "fox and dog play".to_string(),
+        ]);
+        calculator
+    }
+
+    #[test]
+    fn write_and_read_index_roundtrip() {
+        let calculator = build_test_calculator();
+
+        let path = std::env::temp_dir().join("tfidf_index_roundtrip_test.bin");
+        calculator.write_index(&path).unwrap();
+        let loaded = TfidfCalculator::read_index(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.n_documents, calculator.n_documents);
+        assert_eq!(loaded.document_frequency, calculator.document_frequency);
+        assert_eq!(loaded.term_frequencies, calculator.term_frequencies);
+        assert_eq!(loaded.document_lengths, calculator.document_lengths);
+    }
+
+    #[test]
+    fn query_returns_results_sorted_descending_by_score() {
+        let calculator = build_test_calculator();
+
+        // Asking for every document should return every scored document,
+        // sorted highest score first.
+        let results = calculator.query("fox dog", 10);
+        assert_eq!(results.len(), 3);
+        for pair in results.windows(2) {
+            assert!(pair[0].1 >= pair[1].1);
         }
     }
 
-    // Print some statistics
-    println!("\nProcessing Summary:");
-    println!("Total documents processed: {}", calculator.n_documents);
-    println!("Total unique terms: {}", calculator.document_frequency.len());
-    println!("Lines per document: {}", LINES_PER_DOCUMENT);
+    #[test]
+    fn query_respects_top_k_bound() {
+        let calculator = build_test_calculator();
 
-    Ok(())
+        let results = calculator.query("fox dog", 2);
+        assert_eq!(results.len(), 2);
+
+        let results = calculator.query("the fox dog", 1);
+        assert_eq!(results.len(), 1);
+        // The single result kept must be the highest-scoring candidate.
+        let full = calculator.query("the fox dog", 10);
+        assert_eq!(results[0], full[0]);
+    }
 }